@@ -0,0 +1,360 @@
+use ab_glyph::Font;
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+
+use glyph_brush::{DefaultSectionHasher, GlyphBrushBuilder as DownstreamBuilder};
+
+use crate::pipeline::GlyphPipeline;
+use crate::{Extra, GlyphBrush, GlyphVertex};
+
+/// Builder for a [`GlyphBrush`](struct.GlyphBrush.html).
+///
+/// Generic over the per-glyph extra data `X` (see
+/// [`to_vertex`](#method.to_vertex)), defaulting to [`Extra`](enum.Extra.html).
+pub struct GlyphBrushBuilder<F, X = Extra, H = DefaultSectionHasher> {
+    inner: DownstreamBuilder<F, H>,
+    depth_test: Option<u32>,
+    max_texture_upload_bytes_per_frame: Option<usize>,
+    _extra: PhantomData<X>,
+}
+
+impl<F: Font> GlyphBrushBuilder<F> {
+    /// Creates a new builder with a single font.
+    pub fn using_font(font: F) -> GlyphBrushBuilder<F> {
+        GlyphBrushBuilder {
+            inner: DownstreamBuilder::using_font(font),
+            depth_test: None,
+            max_texture_upload_bytes_per_frame: None,
+            _extra: PhantomData,
+        }
+    }
+
+    /// Creates a new builder with multiple fonts.
+    pub fn using_fonts(fonts: Vec<F>) -> GlyphBrushBuilder<F> {
+        GlyphBrushBuilder {
+            inner: DownstreamBuilder::using_fonts(fonts),
+            depth_test: None,
+            max_texture_upload_bytes_per_frame: None,
+            _extra: PhantomData,
+        }
+    }
+}
+
+impl<F, X, H: BuildHasher> GlyphBrushBuilder<F, X, H> {
+    /// Sets the initial cache texture size (in physical pixels). Defaults to
+    /// a small size that will grow as needed.
+    pub fn initial_cache_size(mut self, size: (u32, u32)) -> Self {
+        self.inner = self.inner.initial_cache_size(size);
+        self
+    }
+
+    /// Sets the position tolerance used by the draw cache to decide whether
+    /// a previously cached glyph can be reused for a new position, in
+    /// pixels. Smaller tolerances (below `1.0`) give crisper, more accurately
+    /// subpixel-positioned text at the cost of more cache entries and more
+    /// frequent atlas growth.
+    ///
+    /// Not preserved by [`GlyphBrush::to_builder`](struct.GlyphBrush.html#method.to_builder).
+    pub fn draw_cache_position_tolerance(mut self, tolerance: f32) -> Self {
+        self.inner = self.inner.draw_cache_position_tolerance(tolerance);
+        self
+    }
+
+    /// Sets the scale tolerance used by the draw cache to decide whether a
+    /// previously cached glyph can be reused for a new scale, in pixels per
+    /// em. Smaller tolerances give crisper text at varying sizes at the cost
+    /// of more cache entries.
+    ///
+    /// Not preserved by [`GlyphBrush::to_builder`](struct.GlyphBrush.html#method.to_builder).
+    pub fn draw_cache_scale_tolerance(mut self, tolerance: f32) -> Self {
+        self.inner = self.inner.draw_cache_scale_tolerance(tolerance);
+        self
+    }
+
+    /// Aligns draw cache rows to 4x4 pixel boundaries, trading a little
+    /// texture space for faster, SIMD-friendly atlas updates.
+    ///
+    /// Not preserved by [`GlyphBrush::to_builder`](struct.GlyphBrush.html#method.to_builder).
+    pub fn draw_cache_align_4x4(mut self, align: bool) -> Self {
+        self.inner = self.inner.draw_cache_align_4x4(align);
+        self
+    }
+
+    /// Sets the section hasher used to deduplicate and cache queued
+    /// sections. Defaults to `glyph_brush`'s `DefaultSectionHasher`.
+    pub fn section_hasher<T: BuildHasher>(
+        self,
+        section_hasher: T,
+    ) -> GlyphBrushBuilder<F, X, T> {
+        GlyphBrushBuilder {
+            inner: self.inner.section_hasher(section_hasher),
+            depth_test: self.depth_test,
+            max_texture_upload_bytes_per_frame: self
+                .max_texture_upload_bytes_per_frame,
+            _extra: PhantomData,
+        }
+    }
+
+    /// Replaces the fonts of this builder, e.g. to carry over
+    /// [`GlyphBrush::to_builder`](struct.GlyphBrush.html#method.to_builder)'s
+    /// fonts while swapping one out for a theme or font-size change.
+    pub fn replace_fonts<F2>(
+        self,
+        font_fn: impl FnOnce(Vec<F>) -> Vec<F2>,
+    ) -> GlyphBrushBuilder<F2, X, H> {
+        GlyphBrushBuilder {
+            inner: self.inner.replace_fonts(font_fn),
+            depth_test: self.depth_test,
+            max_texture_upload_bytes_per_frame: self
+                .max_texture_upload_bytes_per_frame,
+            _extra: PhantomData,
+        }
+    }
+
+    /// Bounds how many bytes of glyph coverage data are uploaded to the GPU
+    /// texture cache per [`draw_queued`] call. When one `process_queued`
+    /// pass dirties more atlas data than fits in this budget, the remaining
+    /// rects are deferred to a later frame and that frame's vertex upload is
+    /// skipped too, so nothing draws against a half-updated atlas; the
+    /// previous frame's text keeps rendering until the atlas catches up.
+    /// Unset (the default) uploads everything queued in a single frame.
+    /// At least one rect is uploaded per call even if it alone exceeds the
+    /// budget.
+    ///
+    /// See [`GlyphBrush::upload_stats`] to profile actual upload cost.
+    ///
+    /// [`draw_queued`]: struct.GlyphBrush.html#method.draw_queued
+    /// [`GlyphBrush::upload_stats`]: struct.GlyphBrush.html#method.upload_stats
+    pub fn max_texture_upload_bytes_per_frame(mut self, bytes: usize) -> Self {
+        self.max_texture_upload_bytes_per_frame = Some(bytes);
+        self
+    }
+
+    /// Enables GPU depth testing for
+    /// [`draw_queued_with_transform_and_depth`], comparing each glyph's `z`
+    /// (from [`Extra`](enum.Extra.html)) against the depth buffer with
+    /// `depth_func` (e.g. `glow::LESS`). Has no effect on the other
+    /// `draw_queued*` methods, which always draw in painter's order.
+    ///
+    /// [`draw_queued_with_transform_and_depth`]: struct.GlyphBrush.html#method.draw_queued_with_transform_and_depth
+    pub fn depth_test(mut self, depth_func: u32) -> Self {
+        self.depth_test = Some(depth_func);
+        self
+    }
+
+    /// Switches this builder to carry `X2` as the per-glyph extra data
+    /// instead of the default [`Extra`](enum.Extra.html), converting each
+    /// positioned glyph to a vertex of type `V` with `to_vertex`.
+    ///
+    /// The resulting [`CustomGlyphBrushBuilder`] requires a matching
+    /// [`GlyphPipeline`](trait.GlyphPipeline.html) implementation (and
+    /// shader) to be supplied to
+    /// [`build`](struct.CustomGlyphBrushBuilder.html#method.build), since the
+    /// built-in `core`/`compatibility` pipelines only know how to draw
+    /// `Extra`. Any [`depth_test`](#method.depth_test) set on this builder
+    /// carries over, handed to `build`'s `build_pipeline` for the custom
+    /// pipeline to act on.
+    pub fn to_vertex<X2, V>(
+        self,
+        to_vertex: fn(GlyphVertex<X2>) -> V,
+    ) -> CustomGlyphBrushBuilder<F, X2, H, V> {
+        CustomGlyphBrushBuilder {
+            inner: self.inner,
+            depth_test: self.depth_test,
+            max_texture_upload_bytes_per_frame: self
+                .max_texture_upload_bytes_per_frame,
+            to_vertex,
+        }
+    }
+}
+
+impl<F: Font, H: BuildHasher> GlyphBrushBuilder<F, Extra, H> {
+    /// Builds a [`GlyphBrush`](struct.GlyphBrush.html) for the given
+    /// [`glow::Context`](../glow/struct.Context.html), choosing an instanced
+    /// pipeline on core/GLES3 contexts and a compatibility pipeline
+    /// otherwise.
+    pub fn build(self, gl: &glow::Context) -> GlyphBrush<F, Extra, H> {
+        GlyphBrush::new(
+            gl,
+            self.inner,
+            self.depth_test,
+            self.max_texture_upload_bytes_per_frame,
+        )
+    }
+}
+
+impl<F, X, H> std::fmt::Debug for GlyphBrushBuilder<F, X, H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GlyphBrushBuilder")
+    }
+}
+
+impl<F: Font + Clone, H: BuildHasher + Default> GlyphBrush<F, Extra, H> {
+    /// Reconstructs a [`GlyphBrushBuilder`] carrying this brush's current
+    /// fonts, cache size, depth-test setting and upload budget, e.g. to
+    /// hot-swap fonts on a theme change without dropping the window/GL
+    /// state:
+    ///
+    /// ```ignore
+    /// glyph_brush = glyph_brush
+    ///     .to_builder()
+    ///     .replace_fonts(|_old_fonts| vec![new_font])
+    ///     .build(gl);
+    /// ```
+    ///
+    /// The section hasher is reset to `H::default()`, since the previous one
+    /// can't generally be cloned back out.
+    ///
+    /// [`draw_cache_position_tolerance`](#method.draw_cache_position_tolerance),
+    /// [`draw_cache_scale_tolerance`](#method.draw_cache_scale_tolerance) and
+    /// [`draw_cache_align_4x4`](#method.draw_cache_align_4x4) are *not*
+    /// carried over: they're consumed into the underlying draw cache at
+    /// build time with no way to read them back out, so the returned
+    /// builder falls back to their defaults. Re-apply them explicitly if
+    /// you relied on non-default values.
+    pub fn to_builder(&self) -> GlyphBrushBuilder<F, Extra, H> {
+        let fonts = self.fonts().to_vec();
+
+        let (cache_width, cache_height, depth_test, max_texture_upload_bytes_per_frame) =
+            match self {
+                GlyphBrush::Core {
+                    glyph_brush,
+                    pipeline,
+                    ..
+                } => (
+                    glyph_brush.texture_dimensions().0,
+                    glyph_brush.texture_dimensions().1,
+                    pipeline.depth_test(),
+                    pipeline.upload_budget(),
+                ),
+                GlyphBrush::Compatibility {
+                    glyph_brush,
+                    pipeline,
+                    ..
+                } => (
+                    glyph_brush.texture_dimensions().0,
+                    glyph_brush.texture_dimensions().1,
+                    pipeline.depth_test(),
+                    pipeline.upload_budget(),
+                ),
+                GlyphBrush::Custom {
+                    glyph_brush,
+                    pipeline,
+                    ..
+                } => (
+                    glyph_brush.texture_dimensions().0,
+                    glyph_brush.texture_dimensions().1,
+                    pipeline.depth_test(),
+                    pipeline.upload_budget(),
+                ),
+            };
+
+        GlyphBrushBuilder {
+            inner: DownstreamBuilder::using_fonts(fonts)
+                .initial_cache_size((cache_width, cache_height))
+                .section_hasher(H::default()),
+            depth_test,
+            max_texture_upload_bytes_per_frame,
+            _extra: PhantomData,
+        }
+    }
+}
+
+/// Builder for a [`GlyphBrush`](struct.GlyphBrush.html) backed by a custom
+/// vertex type `V`, obtained from
+/// [`GlyphBrushBuilder::to_vertex`](struct.GlyphBrushBuilder.html#method.to_vertex).
+pub struct CustomGlyphBrushBuilder<F, X, H, V> {
+    inner: DownstreamBuilder<F, H>,
+    depth_test: Option<u32>,
+    max_texture_upload_bytes_per_frame: Option<usize>,
+    to_vertex: fn(GlyphVertex<X>) -> V,
+}
+
+impl<F, X, H: BuildHasher, V> CustomGlyphBrushBuilder<F, X, H, V> {
+    /// Sets the initial cache texture size (in physical pixels). Defaults to
+    /// a small size that will grow as needed.
+    pub fn initial_cache_size(mut self, size: (u32, u32)) -> Self {
+        self.inner = self.inner.initial_cache_size(size);
+        self
+    }
+
+    /// Sets the depth comparison function `build_pipeline`'s pipeline is
+    /// handed, see
+    /// [`GlyphBrushBuilder::depth_test`](struct.GlyphBrushBuilder.html#method.depth_test).
+    /// Acting on it (and on [`GlyphPipeline::draw_with_depth`]'s
+    /// `depth_func`) is up to the custom pipeline; built-in pipelines are
+    /// the only ones that do so automatically.
+    ///
+    /// [`GlyphPipeline::draw_with_depth`]: trait.GlyphPipeline.html#method.draw_with_depth
+    pub fn depth_test(mut self, depth_func: u32) -> Self {
+        self.depth_test = Some(depth_func);
+        self
+    }
+
+    /// Bounds how many bytes of glyph coverage data `build_pipeline`'s
+    /// pipeline uploads to its texture cache per frame, see
+    /// [`GlyphBrushBuilder::max_texture_upload_bytes_per_frame`](struct.GlyphBrushBuilder.html#method.max_texture_upload_bytes_per_frame).
+    pub fn max_texture_upload_bytes_per_frame(mut self, bytes: usize) -> Self {
+        self.max_texture_upload_bytes_per_frame = Some(bytes);
+        self
+    }
+
+    /// Sets the position tolerance used by the draw cache, see
+    /// [`GlyphBrushBuilder::draw_cache_position_tolerance`](struct.GlyphBrushBuilder.html#method.draw_cache_position_tolerance).
+    pub fn draw_cache_position_tolerance(mut self, tolerance: f32) -> Self {
+        self.inner = self.inner.draw_cache_position_tolerance(tolerance);
+        self
+    }
+
+    /// Sets the scale tolerance used by the draw cache, see
+    /// [`GlyphBrushBuilder::draw_cache_scale_tolerance`](struct.GlyphBrushBuilder.html#method.draw_cache_scale_tolerance).
+    pub fn draw_cache_scale_tolerance(mut self, tolerance: f32) -> Self {
+        self.inner = self.inner.draw_cache_scale_tolerance(tolerance);
+        self
+    }
+
+    /// Aligns draw cache rows to 4x4 pixel boundaries, see
+    /// [`GlyphBrushBuilder::draw_cache_align_4x4`](struct.GlyphBrushBuilder.html#method.draw_cache_align_4x4).
+    pub fn draw_cache_align_4x4(mut self, align: bool) -> Self {
+        self.inner = self.inner.draw_cache_align_4x4(align);
+        self
+    }
+}
+
+impl<F: Font, X, H: BuildHasher, V> CustomGlyphBrushBuilder<F, X, H, V> {
+    /// Builds a [`GlyphBrush`](struct.GlyphBrush.html) backed by
+    /// `build_pipeline`, which is handed the `glow::Context`, the cache's
+    /// initial dimensions, this builder's
+    /// [`depth_test`](#method.depth_test) (or `None`), and its
+    /// [`max_texture_upload_bytes_per_frame`](#method.max_texture_upload_bytes_per_frame)
+    /// (or `None`), mirroring how the built-in `core` and `compatibility`
+    /// pipelines are constructed internally.
+    pub fn build<P: GlyphPipeline<V>>(
+        self,
+        gl: &glow::Context,
+        build_pipeline: fn(&glow::Context, u32, u32, Option<u32>, Option<usize>) -> P,
+    ) -> GlyphBrush<F, X, H, V, P> {
+        let glyph_brush = self.inner.build();
+        let (cache_width, cache_height) = glyph_brush.texture_dimensions();
+
+        GlyphBrush::Custom {
+            pipeline: build_pipeline(
+                gl,
+                cache_width,
+                cache_height,
+                self.depth_test,
+                self.max_texture_upload_bytes_per_frame,
+            ),
+            glyph_brush,
+            to_vertex: self.to_vertex,
+            pending_verts: None,
+        }
+    }
+}
+
+impl<F, X, H, V> std::fmt::Debug for CustomGlyphBrushBuilder<F, X, H, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomGlyphBrushBuilder")
+    }
+}
+
@@ -8,14 +8,16 @@ mod pipeline;
 mod region;
 
 use pipeline::{compatibility, core};
+pub use pipeline::{GlyphPipeline, UploadStats};
 pub use region::Region;
 
-pub use builder::GlyphBrushBuilder;
+pub use builder::{CustomGlyphBrushBuilder, GlyphBrushBuilder};
 pub use glyph_brush::ab_glyph;
 pub use glyph_brush::{
     BuiltInLineBreaker, Extra, FontId, GlyphCruncher, GlyphPositioner,
-    HorizontalAlign, Layout, LineBreak, LineBreaker, Section, SectionGeometry,
-    SectionGlyph, SectionGlyphIter, SectionText, Text, VerticalAlign,
+    GlyphVertex, HorizontalAlign, Layout, LineBreak, LineBreaker, Section,
+    SectionGeometry, SectionGlyph, SectionGlyphIter, SectionText, Text,
+    VerticalAlign,
 };
 
 use ab_glyph::{Font, FontArc, Rect};
@@ -29,20 +31,46 @@ use log::{log_enabled, warn};
 /// Object allowing glyph drawing, containing cache state. Manages glyph positioning cacheing,
 /// glyph draw caching & efficient GPU texture cache updating and re-sizing on demand.
 ///
+/// Generic over the per-glyph extra data `X` (defaults to [`Extra`], i.e.
+/// color and z) and, for callers supplying their own [`GlyphPipeline`] via
+/// [`GlyphBrushBuilder::to_vertex`](struct.GlyphBrushBuilder.html#method.to_vertex),
+/// the vertex type `V` and pipeline type `P` it draws.
+///
 /// Build using a [`GlyphBrushBuilder`](struct.GlyphBrushBuilder.html).
-pub enum GlyphBrush<F = FontArc, H = DefaultSectionHasher> {
+pub enum GlyphBrush<
+    F = FontArc,
+    X = Extra,
+    H = DefaultSectionHasher,
+    V = core::Instance,
+    P = core::Pipeline,
+> {
     Core {
         pipeline: core::Pipeline,
-        glyph_brush: glyph_brush::GlyphBrush<core::Instance, Extra, F, H>,
+        glyph_brush: glyph_brush::GlyphBrush<core::Instance, X, F, H>,
+        to_vertex: fn(GlyphVertex<X>) -> core::Instance,
+        // Vertices computed by the last `process_queued` call whose upload
+        // the pipeline's byte budget couldn't fit in yet; retried once
+        // `has_pending_uploads` clears instead of being dropped.
+        pending_verts: Option<Vec<core::Instance>>,
     },
     Compatibility {
         pipeline: compatibility::Pipeline,
         glyph_brush:
-            glyph_brush::GlyphBrush<[compatibility::Vertex; 4], Extra, F, H>,
+            glyph_brush::GlyphBrush<[compatibility::Vertex; 4], X, F, H>,
+        to_vertex: fn(GlyphVertex<X>) -> [compatibility::Vertex; 4],
+        pending_verts: Option<Vec<[compatibility::Vertex; 4]>>,
+    },
+    /// Backed by a caller-supplied [`GlyphPipeline`] and `to_vertex`
+    /// conversion, for per-glyph data the built-in pipelines can't express.
+    Custom {
+        pipeline: P,
+        glyph_brush: glyph_brush::GlyphBrush<V, X, F, H>,
+        to_vertex: fn(GlyphVertex<X>) -> V,
+        pending_verts: Option<Vec<V>>,
     },
 }
 
-impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
+impl<F: Font, X, H: BuildHasher, V, P: GlyphPipeline<V>> GlyphBrush<F, X, H, V, P> {
     /// Queues a section/layout to be drawn by the next call of
     /// [`draw_queued`](struct.GlyphBrush.html#method.draw_queued). Can be
     /// called multiple times to queue multiple sections for drawing.
@@ -58,6 +86,9 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
                 glyph_brush.queue(section)
             }
             GlyphBrush::Core { glyph_brush, .. } => glyph_brush.queue(section),
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.queue(section)
+            }
         }
     }
 
@@ -86,6 +117,9 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.queue_custom_layout(section, custom_layout)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.queue_custom_layout(section, custom_layout)
+            }
         }
     }
 
@@ -96,7 +130,7 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
     pub fn queue_pre_positioned(
         &mut self,
         glyphs: Vec<SectionGlyph>,
-        extra: Vec<Extra>,
+        extra: Vec<X>,
         bounds: Rect,
     ) {
         match self {
@@ -106,6 +140,9 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.queue_pre_positioned(glyphs, extra, bounds)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.queue_pre_positioned(glyphs, extra, bounds)
+            }
         }
     }
 
@@ -130,6 +167,9 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.keep_cached_custom_layout(section, custom_layout)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.keep_cached_custom_layout(section, custom_layout)
+            }
         }
     }
 
@@ -150,6 +190,9 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.keep_cached(section)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.keep_cached(section)
+            }
         }
     }
 
@@ -163,6 +206,7 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
                 glyph_brush.fonts()
             }
             GlyphBrush::Core { glyph_brush, .. } => glyph_brush.fonts(),
+            GlyphBrush::Custom { glyph_brush, .. } => glyph_brush.fonts(),
         }
     }
 
@@ -175,11 +219,30 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
                 glyph_brush.add_font(font)
             }
             GlyphBrush::Core { glyph_brush, .. } => glyph_brush.add_font(font),
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.add_font(font)
+            }
+        }
+    }
+
+    /// Returns stats (rects and bytes uploaded) from the texture-cache flush
+    /// of the last `draw_queued*` call (all zero if that call had nothing
+    /// to upload), see
+    /// [`GlyphBrushBuilder::max_texture_upload_bytes_per_frame`](struct.GlyphBrushBuilder.html#method.max_texture_upload_bytes_per_frame).
+    pub fn upload_stats(&self) -> UploadStats {
+        match self {
+            GlyphBrush::Compatibility { pipeline, .. } => {
+                pipeline.upload_stats()
+            }
+            GlyphBrush::Core { pipeline, .. } => pipeline.upload_stats(),
+            GlyphBrush::Custom { pipeline, .. } => pipeline.upload_stats(),
         }
     }
 }
 
-impl<F: Font + Sync, H: BuildHasher> GlyphBrush<F, H> {
+impl<F: Font + Sync, X, H: BuildHasher, V, P: GlyphPipeline<V>>
+    GlyphBrush<F, X, H, V, P>
+{
     /// Draws all queued sections onto a render target.
     /// See [`queue`](struct.GlyphBrush.html#method.queue).
     ///
@@ -225,6 +288,9 @@ impl<F: Font + Sync, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { pipeline, .. } => {
                 pipeline.draw(context, transform, None);
             }
+            GlyphBrush::Custom { pipeline, .. } => {
+                pipeline.draw(context, transform, None);
+            }
         }
 
         Ok(())
@@ -255,6 +321,63 @@ impl<F: Font + Sync, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Core { pipeline, .. } => {
                 pipeline.draw(context, transform, Some(region));
             }
+            GlyphBrush::Custom { pipeline, .. } => {
+                pipeline.draw(context, transform, Some(region));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws all queued sections onto a render target, applying a position
+    /// transform (e.g. a 3D projection) and an optional scissoring region,
+    /// with `GL_DEPTH_TEST` enabled so glyphs are depth-tested against a
+    /// surrounding 3D scene using each glyph's `z`
+    /// (see [`Extra`](enum.Extra.html)).
+    ///
+    /// Uses the depth comparison function set with
+    /// [`GlyphBrushBuilder::depth_test`](struct.GlyphBrushBuilder.html#method.depth_test)
+    /// (or [`CustomGlyphBrushBuilder::depth_test`](struct.CustomGlyphBrushBuilder.html#method.depth_test)
+    /// for a [`GlyphPipeline`](trait.GlyphPipeline.html) that reports it via
+    /// [`GlyphPipeline::depth_test`](trait.GlyphPipeline.html#method.depth_test)),
+    /// defaulting to `glow::LESS` if none was set. The prior
+    /// `GL_DEPTH_TEST`/depth-func/depth-mask state is saved and restored, so
+    /// this won't clobber the caller's own pipeline. This is what the
+    /// built-in pipelines do; a custom [`GlyphPipeline`] handles depth in its
+    /// own `draw_with_depth`, whose default implementation draws without
+    /// depth testing.
+    /// See [`queue`](struct.GlyphBrush.html#method.queue).
+    ///
+    /// Trims the cache, see [caching behaviour](#caching-behaviour).
+    ///
+    /// # Panics
+    /// Panics if the provided `target` has a texture format that does not match
+    /// the `render_format` provided on creation of the `GlyphBrush`.
+    #[inline]
+    pub fn draw_queued_with_transform_and_depth(
+        &mut self,
+        context: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+    ) -> Result<(), String> {
+        self.process_queued(context);
+
+        match self {
+            GlyphBrush::Compatibility { pipeline, .. } => {
+                let depth_func =
+                    pipeline.depth_test().unwrap_or(glow::LESS);
+                pipeline.draw_with_depth(context, transform, region, depth_func);
+            }
+            GlyphBrush::Core { pipeline, .. } => {
+                let depth_func =
+                    pipeline.depth_test().unwrap_or(glow::LESS);
+                pipeline.draw_with_depth(context, transform, region, depth_func);
+            }
+            GlyphBrush::Custom { pipeline, .. } => {
+                let depth_func =
+                    pipeline.depth_test().unwrap_or(glow::LESS);
+                pipeline.draw_with_depth(context, transform, region, depth_func);
+            }
         }
 
         Ok(())
@@ -265,139 +388,137 @@ impl<F: Font + Sync, H: BuildHasher> GlyphBrush<F, H> {
             GlyphBrush::Compatibility {
                 glyph_brush,
                 pipeline,
-            } => {
-                let mut brush_action;
-
-                loop {
-                    brush_action = glyph_brush.process_queued(
-                        |rect, tex_data| {
-                            let offset =
-                                [rect.min[0] as u16, rect.min[1] as u16];
-                            let size =
-                                [rect.width() as u16, rect.height() as u16];
-
-                            pipeline
-                                .update_cache(context, offset, size, tex_data);
-                        },
-                        |glyph| compatibility::Vertex::from_vertex(&glyph),
-                    );
-
-                    match brush_action {
-                        Ok(_) => break,
-                        Err(BrushError::TextureTooSmall { suggested }) => {
-                            let max_image_size =
-                                pipeline.get_max_texture_size();
-
-                            let (new_width, new_height) = if (suggested.0
-                                > max_image_size
-                                || suggested.1 > max_image_size)
-                                && (glyph_brush.texture_dimensions().0
-                                    < max_image_size
-                                    || glyph_brush.texture_dimensions().1
-                                        < max_image_size)
-                            {
-                                (max_image_size, max_image_size)
-                            } else {
-                                suggested
-                            };
-
-                            if log_enabled!(log::Level::Warn) {
-                                warn!(
-                            "Increasing glyph texture size {old:?} -> {new:?}. \
-                             Consider building with `.initial_cache_size({new:?})` to avoid \
-                             resizing",
-                            old = glyph_brush.texture_dimensions(),
-                            new = (new_width, new_height),
-                        );
-                            }
-
-                            pipeline.increase_cache_size(
-                                context, new_width, new_height,
-                            );
-                            glyph_brush.resize_texture(new_width, new_height);
-                        }
-                    }
-                }
-
-                match brush_action.unwrap() {
-                    BrushAction::Draw(verts) => {
-                        pipeline.upload(context, &verts);
-                    }
-                    BrushAction::ReDraw => {}
-                };
-            }
+                to_vertex,
+                pending_verts,
+            } => process_queued_with_pipeline(
+                context,
+                glyph_brush,
+                pipeline,
+                *to_vertex,
+                pending_verts,
+            ),
             GlyphBrush::Core {
                 glyph_brush,
                 pipeline,
-            } => {
-                let mut brush_action;
-
-                loop {
-                    brush_action = glyph_brush.process_queued(
-                        |rect, tex_data| {
-                            let offset =
-                                [rect.min[0] as u16, rect.min[1] as u16];
-                            let size =
-                                [rect.width() as u16, rect.height() as u16];
-
-                            pipeline
-                                .update_cache(context, offset, size, tex_data);
-                        },
-                        core::Instance::from_vertex,
-                    );
+                to_vertex,
+                pending_verts,
+            } => process_queued_with_pipeline(
+                context,
+                glyph_brush,
+                pipeline,
+                *to_vertex,
+                pending_verts,
+            ),
+            GlyphBrush::Custom {
+                glyph_brush,
+                pipeline,
+                to_vertex,
+                pending_verts,
+            } => process_queued_with_pipeline(
+                context,
+                glyph_brush,
+                pipeline,
+                *to_vertex,
+                pending_verts,
+            ),
+        }
+    }
+}
+
+/// Shared body of [`GlyphBrush::process_queued`] for whichever concrete
+/// `glyph_brush`/`pipeline` pair a variant holds: runs `process_queued`,
+/// growing and repacking the atlas (and re-staging every glyph) as many
+/// times as `TextureTooSmall` demands, then flushes the staged cache
+/// updates and uploads the resulting vertices.
+///
+/// `pending_verts` holds the most recent vertices computed from
+/// `BrushAction::Draw` that the pipeline's upload budget hasn't caught up
+/// with yet; they're retried here once `has_pending_uploads` clears,
+/// rather than being dropped (`glyph_brush::GlyphBrush::process_queued`
+/// only recomputes vertices when the queued content actually changes, so
+/// a dropped batch wouldn't otherwise be retried).
+fn process_queued_with_pipeline<V, X, F: Font + Sync, H: BuildHasher>(
+    context: &glow::Context,
+    glyph_brush: &mut glyph_brush::GlyphBrush<V, X, F, H>,
+    pipeline: &mut impl GlyphPipeline<V>,
+    to_vertex: fn(GlyphVertex<X>) -> V,
+    pending_verts: &mut Option<Vec<V>>,
+) {
+    let mut brush_action;
+
+    loop {
+        brush_action = glyph_brush.process_queued(
+            |rect, tex_data| {
+                let offset = [rect.min[0] as u16, rect.min[1] as u16];
+                let size = [rect.width() as u16, rect.height() as u16];
+
+                pipeline.stage_cache_update(offset, size, tex_data);
+            },
+            |glyph| to_vertex(glyph),
+        );
+
+        match brush_action {
+            Ok(_) => break,
+            Err(BrushError::TextureTooSmall { suggested }) => {
+                let max_image_size = pipeline.get_max_texture_size();
+
+                let (new_width, new_height) = if (suggested.0
+                    > max_image_size
+                    || suggested.1 > max_image_size)
+                    && (glyph_brush.texture_dimensions().0 < max_image_size
+                        || glyph_brush.texture_dimensions().1
+                            < max_image_size)
+                {
+                    (max_image_size, max_image_size)
+                } else {
+                    suggested
+                };
 
-                    match brush_action {
-                        Ok(_) => break,
-                        Err(BrushError::TextureTooSmall { suggested }) => {
-                            let max_image_size =
-                                pipeline.get_max_texture_size();
-
-                            let (new_width, new_height) = if (suggested.0
-                                > max_image_size
-                                || suggested.1 > max_image_size)
-                                && (glyph_brush.texture_dimensions().0
-                                    < max_image_size
-                                    || glyph_brush.texture_dimensions().1
-                                        < max_image_size)
-                            {
-                                (max_image_size, max_image_size)
-                            } else {
-                                suggested
-                            };
-
-                            if log_enabled!(log::Level::Warn) {
-                                warn!(
-                            "Increasing glyph texture size {old:?} -> {new:?}. \
-                             Consider building with `.initial_cache_size({new:?})` to avoid \
-                             resizing",
-                            old = glyph_brush.texture_dimensions(),
-                            new = (new_width, new_height),
-                        );
-                            }
-
-                            pipeline.increase_cache_size(
-                                context, new_width, new_height,
-                            );
-                            glyph_brush.resize_texture(new_width, new_height);
-                        }
-                    }
+                if log_enabled!(log::Level::Warn) {
+                    warn!(
+                        "Increasing glyph texture size {old:?} -> {new:?}. \
+                         Consider building with `.initial_cache_size({new:?})` to avoid \
+                         resizing",
+                        old = glyph_brush.texture_dimensions(),
+                        new = (new_width, new_height),
+                    );
                 }
 
-                match brush_action.unwrap() {
-                    BrushAction::Draw(verts) => {
-                        pipeline.upload(context, &verts);
-                    }
-                    BrushAction::ReDraw => {}
-                };
+                pipeline.increase_cache_size(context, new_width, new_height);
+                glyph_brush.resize_texture(new_width, new_height);
+
+                // Cached vertices reference atlas UVs from before the
+                // repack; they'd sample the wrong glyph once re-uploaded.
+                *pending_verts = None;
             }
         }
     }
+
+    let _stats = pipeline.flush_cache_updates(context);
+
+    if let BrushAction::Draw(verts) = brush_action.unwrap() {
+        *pending_verts = Some(verts);
+    }
+
+    // If the upload budget left rects still staged, the atlas isn't fully
+    // up to date yet; hold onto the vertices and retry the upload once it
+    // catches up, instead of drawing against a half-updated atlas now and
+    // never drawing this batch again. (If a resize just ran,
+    // increase_cache_size already zeroed the pipeline's vertex count, so
+    // this frame draws nothing rather than stale or garbled glyphs.)
+    if !pipeline.has_pending_uploads() {
+        if let Some(verts) = pending_verts.take() {
+            pipeline.upload(context, &verts);
+        }
+    }
 }
 
-impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
+impl<F: Font, H: BuildHasher> GlyphBrush<F, Extra, H> {
     fn new(
         gl: &glow::Context,
         raw_builder: glyph_brush::GlyphBrushBuilder<F, H>,
+        depth_test: Option<u32>,
+        upload_budget: Option<usize>,
     ) -> Self {
         use glow::HasContext;
 
@@ -410,8 +531,16 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
             let (cache_width, cache_height) = glyph_brush.texture_dimensions();
 
             GlyphBrush::Core {
-                pipeline: core::Pipeline::new(gl, cache_width, cache_height),
+                pipeline: core::Pipeline::new(
+                    gl,
+                    cache_width,
+                    cache_height,
+                    depth_test,
+                    upload_budget,
+                ),
                 glyph_brush,
+                to_vertex: core::Instance::from_vertex,
+                pending_verts: None,
             }
         } else {
             log::info!("Mode: compatibility");
@@ -424,8 +553,12 @@ impl<F: Font, H: BuildHasher> GlyphBrush<F, H> {
                     gl,
                     cache_width,
                     cache_height,
+                    depth_test,
+                    upload_budget,
                 ),
                 glyph_brush,
+                to_vertex: |glyph| compatibility::Vertex::from_vertex(&glyph),
+                pending_verts: None,
             }
         }
     }
@@ -442,7 +575,9 @@ pub fn orthographic_projection(width: u32, height: u32) -> [f32; 16] {
     ]
 }
 
-impl<F: Font, H: BuildHasher> GlyphCruncher<F> for GlyphBrush<F, H> {
+impl<F: Font, X, H: BuildHasher, V, P: GlyphPipeline<V>> GlyphCruncher<F>
+    for GlyphBrush<F, X, H, V, P>
+{
     #[inline]
     fn glyphs_custom_layout<'a, 'b, S, L>(
         &'b mut self,
@@ -460,6 +595,9 @@ impl<F: Font, H: BuildHasher> GlyphCruncher<F> for GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.glyphs_custom_layout(section, custom_layout)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.glyphs_custom_layout(section, custom_layout)
+            }
         }
     }
 
@@ -480,6 +618,9 @@ impl<F: Font, H: BuildHasher> GlyphCruncher<F> for GlyphBrush<F, H> {
             GlyphBrush::Core { glyph_brush, .. } => {
                 glyph_brush.glyph_bounds_custom_layout(section, custom_layout)
             }
+            GlyphBrush::Custom { glyph_brush, .. } => {
+                glyph_brush.glyph_bounds_custom_layout(section, custom_layout)
+            }
         }
     }
 
@@ -490,11 +631,12 @@ impl<F: Font, H: BuildHasher> GlyphCruncher<F> for GlyphBrush<F, H> {
                 glyph_brush.fonts()
             }
             GlyphBrush::Core { glyph_brush, .. } => glyph_brush.fonts(),
+            GlyphBrush::Custom { glyph_brush, .. } => glyph_brush.fonts(),
         }
     }
 }
 
-impl<F, H> std::fmt::Debug for GlyphBrush<F, H> {
+impl<F, X, H, V, P> std::fmt::Debug for GlyphBrush<F, X, H, V, P> {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "GlyphBrush")
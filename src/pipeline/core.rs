@@ -0,0 +1,451 @@
+//! Instanced rendering pipeline used on OpenGL 3.3+ / GLES 3.0+ contexts.
+use std::collections::VecDeque;
+
+use glow::HasContext;
+
+use crate::Region;
+
+use super::{PendingUpload, UploadStats};
+
+const VERTEX_SHADER: &str = include_str!("shader/core.vert");
+const FRAGMENT_SHADER: &str = include_str!("shader/core.frag");
+
+/// Per-glyph instance data uploaded to the GPU. One `Instance` renders a
+/// single glyph quad via instanced drawing.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Instance {
+    left_top: [f32; 3],
+    right_bottom: [f32; 2],
+    tex_left_top: [f32; 2],
+    tex_right_bottom: [f32; 2],
+    color: [f32; 4],
+}
+
+impl Instance {
+    pub fn from_vertex(
+        vertex: glyph_brush::GlyphVertex<crate::Extra>,
+    ) -> Instance {
+        let glyph_brush::GlyphVertex {
+            mut tex_coords,
+            pixel_coords,
+            bounds,
+            extra,
+        } = vertex;
+
+        let mut gl_rect = pixel_coords;
+
+        // Clip the quad (and correspondingly the texture coordinates) to the
+        // layout bounds, so glyphs that overflow their section don't draw.
+        if gl_rect.max.x > bounds.max.x {
+            let old_width = gl_rect.width();
+            gl_rect.max.x = bounds.max.x;
+            tex_coords.max.x = tex_coords.min.x
+                + tex_coords.width() * gl_rect.width() / old_width;
+        }
+        if gl_rect.min.x < bounds.min.x {
+            let old_width = gl_rect.width();
+            gl_rect.min.x = bounds.min.x;
+            tex_coords.min.x = tex_coords.max.x
+                - tex_coords.width() * gl_rect.width() / old_width;
+        }
+        if gl_rect.max.y > bounds.max.y {
+            let old_height = gl_rect.height();
+            gl_rect.max.y = bounds.max.y;
+            tex_coords.max.y = tex_coords.min.y
+                + tex_coords.height() * gl_rect.height() / old_height;
+        }
+        if gl_rect.min.y < bounds.min.y {
+            let old_height = gl_rect.height();
+            gl_rect.min.y = bounds.min.y;
+            tex_coords.min.y = tex_coords.max.y
+                - tex_coords.height() * gl_rect.height() / old_height;
+        }
+
+        Instance {
+            left_top: [gl_rect.min.x, gl_rect.max.y, extra.z],
+            right_bottom: [gl_rect.max.x, gl_rect.min.y],
+            tex_left_top: [tex_coords.min.x, tex_coords.max.y],
+            tex_right_bottom: [tex_coords.max.x, tex_coords.min.y],
+            color: extra.color,
+        }
+    }
+}
+
+/// GPU resources backing text rendering on a "core profile" context.
+pub struct Pipeline {
+    program: glow::Program,
+    vertex_array: glow::VertexArray,
+    instances: glow::Buffer,
+    instance_count: usize,
+    texture: glow::Texture,
+    texture_width: u32,
+    texture_height: u32,
+    transform_location: glow::UniformLocation,
+    depth_test: Option<u32>,
+    pending_uploads: VecDeque<PendingUpload>,
+    upload_budget: Option<usize>,
+    upload_stats: UploadStats,
+}
+
+impl Pipeline {
+    pub fn new(
+        gl: &glow::Context,
+        cache_width: u32,
+        cache_height: u32,
+        depth_test: Option<u32>,
+        upload_budget: Option<usize>,
+    ) -> Pipeline {
+        unsafe {
+            let program = gl.create_program().expect("Create program");
+
+            let vertex_shader = compile_shader(
+                gl,
+                glow::VERTEX_SHADER,
+                VERTEX_SHADER,
+            );
+            let fragment_shader = compile_shader(
+                gl,
+                glow::FRAGMENT_SHADER,
+                FRAGMENT_SHADER,
+            );
+
+            gl.attach_shader(program, vertex_shader);
+            gl.attach_shader(program, fragment_shader);
+            gl.link_program(program);
+
+            if !gl.get_program_link_status(program) {
+                panic!("{}", gl.get_program_info_log(program));
+            }
+
+            gl.delete_shader(vertex_shader);
+            gl.delete_shader(fragment_shader);
+
+            let vertex_array =
+                gl.create_vertex_array().expect("Create vertex array");
+            gl.bind_vertex_array(Some(vertex_array));
+
+            let instances = gl.create_buffer().expect("Create instance buffer");
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(instances));
+
+            let stride = std::mem::size_of::<Instance>() as i32;
+
+            for (location, size, offset) in &[
+                (0u32, 3, 0),
+                (1, 2, 3 * 4),
+                (2, 2, 5 * 4),
+                (3, 2, 7 * 4),
+                (4, 4, 9 * 4),
+            ] {
+                gl.enable_vertex_attrib_array(*location);
+                gl.vertex_attrib_pointer_f32(
+                    *location,
+                    *size,
+                    glow::FLOAT,
+                    false,
+                    stride,
+                    *offset,
+                );
+                gl.vertex_attrib_divisor(*location, 1);
+            }
+
+            let texture = gl.create_texture().expect("Create glyph texture");
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::LINEAR as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::LINEAR as i32,
+            );
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::R8 as i32,
+                cache_width as i32,
+                cache_height as i32,
+                0,
+                glow::RED,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+
+            let transform_location = gl
+                .get_uniform_location(program, "transform")
+                .expect("Get transform location");
+
+            Pipeline {
+                program,
+                vertex_array,
+                instances,
+                instance_count: 0,
+                texture,
+                texture_width: cache_width,
+                texture_height: cache_height,
+                transform_location,
+                depth_test,
+                pending_uploads: VecDeque::new(),
+                upload_budget,
+                upload_stats: UploadStats::default(),
+            }
+        }
+    }
+
+    pub fn get_max_texture_size(&self) -> u32 {
+        // A fixed value; the driver's actual GL_MAX_TEXTURE_SIZE is not
+        // queried.
+        16384
+    }
+
+    pub fn stage_cache_update(
+        &mut self,
+        offset: [u16; 2],
+        size: [u16; 2],
+        data: &[u8],
+    ) {
+        self.pending_uploads.push_back(PendingUpload {
+            offset,
+            size,
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn flush_cache_updates(&mut self, gl: &glow::Context) -> UploadStats {
+        let stats = super::flush_pending_uploads(
+            gl,
+            self.texture,
+            glow::RED,
+            &mut self.pending_uploads,
+            self.upload_budget,
+        );
+        self.upload_stats = stats;
+        stats
+    }
+
+    pub fn has_pending_uploads(&self) -> bool {
+        !self.pending_uploads.is_empty()
+    }
+
+    pub fn increase_cache_size(
+        &mut self,
+        gl: &glow::Context,
+        width: u32,
+        height: u32,
+    ) {
+        self.texture_width = width;
+        self.texture_height = height;
+
+        // Rects staged against the old atlas packing would blit to the
+        // wrong offsets once it's resized and glyphs are repacked.
+        self.pending_uploads.clear();
+        self.upload_stats = UploadStats::default();
+
+        // The vertices last uploaded sample UVs from the atlas this call
+        // just destroyed; draw nothing until a vertex buffer matching the
+        // new layout is uploaded, rather than garbled glyphs.
+        self.instance_count = 0;
+
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::R8 as i32,
+                width as i32,
+                height as i32,
+                0,
+                glow::RED,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+        }
+    }
+
+    pub fn upload(&mut self, gl: &glow::Context, instances: &[Instance]) {
+        self.instance_count = instances.len();
+
+        unsafe {
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.instances));
+
+            let data = std::slice::from_raw_parts(
+                instances.as_ptr() as *const u8,
+                instances.len() * std::mem::size_of::<Instance>(),
+            );
+
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, data, glow::DYNAMIC_DRAW);
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+    ) {
+        self.render(gl, transform, region);
+    }
+
+    /// Like [`draw`](#method.draw), but enables `GL_DEPTH_TEST` with
+    /// `depth_func` for the duration of the call, so glyphs are correctly
+    /// occluded by (and occlude) a 3D scene using the `z` written by the
+    /// vertex shader. The previous depth-test state is restored afterwards.
+    pub fn draw_with_depth(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+        depth_func: u32,
+    ) {
+        unsafe {
+            let was_enabled = gl.is_enabled(glow::DEPTH_TEST);
+            let prior_func = gl.get_parameter_i32(glow::DEPTH_FUNC) as u32;
+            let prior_mask = gl.get_parameter_i32(glow::DEPTH_WRITEMASK) != 0;
+
+            gl.enable(glow::DEPTH_TEST);
+            gl.depth_func(depth_func);
+            gl.depth_mask(true);
+
+            self.render(gl, transform, region);
+
+            gl.depth_mask(prior_mask);
+            gl.depth_func(prior_func);
+            if !was_enabled {
+                gl.disable(glow::DEPTH_TEST);
+            }
+        }
+    }
+
+    fn render(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+    ) {
+        if self.instance_count == 0 {
+            return;
+        }
+
+        unsafe {
+            gl.use_program(Some(self.program));
+            gl.bind_vertex_array(Some(self.vertex_array));
+
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+
+            gl.uniform_matrix_4_f32_slice(
+                Some(&self.transform_location),
+                false,
+                &transform,
+            );
+
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+
+            if let Some(region) = region {
+                gl.enable(glow::SCISSOR_TEST);
+                gl.scissor(
+                    region.x as i32,
+                    region.y as i32,
+                    region.width as i32,
+                    region.height as i32,
+                );
+            }
+
+            gl.draw_arrays_instanced(
+                glow::TRIANGLE_STRIP,
+                0,
+                4,
+                self.instance_count as i32,
+            );
+
+            if region.is_some() {
+                gl.disable(glow::SCISSOR_TEST);
+            }
+        }
+    }
+
+    pub fn depth_test(&self) -> Option<u32> {
+        self.depth_test
+    }
+
+    pub fn upload_budget(&self) -> Option<usize> {
+        self.upload_budget
+    }
+}
+
+unsafe fn compile_shader(
+    gl: &glow::Context,
+    shader_type: u32,
+    source: &str,
+) -> glow::Shader {
+    let shader = gl.create_shader(shader_type).expect("Create shader");
+    gl.shader_source(shader, source);
+    gl.compile_shader(shader);
+
+    if !gl.get_shader_compile_status(shader) {
+        panic!("{}", gl.get_shader_info_log(shader));
+    }
+
+    shader
+}
+
+impl super::GlyphPipeline<Instance> for Pipeline {
+    fn stage_cache_update(
+        &mut self,
+        offset: [u16; 2],
+        size: [u16; 2],
+        data: &[u8],
+    ) {
+        Pipeline::stage_cache_update(self, offset, size, data)
+    }
+
+    fn flush_cache_updates(&mut self, gl: &glow::Context) -> UploadStats {
+        Pipeline::flush_cache_updates(self, gl)
+    }
+
+    fn has_pending_uploads(&self) -> bool {
+        Pipeline::has_pending_uploads(self)
+    }
+
+    fn upload_stats(&self) -> UploadStats {
+        self.upload_stats
+    }
+
+    fn depth_test(&self) -> Option<u32> {
+        Pipeline::depth_test(self)
+    }
+
+    fn increase_cache_size(&mut self, gl: &glow::Context, width: u32, height: u32) {
+        Pipeline::increase_cache_size(self, gl, width, height)
+    }
+
+    fn upload(&mut self, gl: &glow::Context, vertices: &[Instance]) {
+        Pipeline::upload(self, gl, vertices)
+    }
+
+    fn draw(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+    ) {
+        Pipeline::draw(self, gl, transform, region)
+    }
+
+    fn get_max_texture_size(&self) -> u32 {
+        Pipeline::get_max_texture_size(self)
+    }
+
+    fn draw_with_depth(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+        depth_func: u32,
+    ) {
+        Pipeline::draw_with_depth(self, gl, transform, region, depth_func)
+    }
+}
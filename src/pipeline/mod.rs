@@ -0,0 +1,180 @@
+pub mod compatibility;
+pub mod core;
+
+use std::collections::VecDeque;
+
+use crate::Region;
+
+/// Rects and bytes uploaded to a pipeline's texture cache by the most recent
+/// [`GlyphPipeline::flush_cache_updates`] call, for profiling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// Number of dirty rects uploaded via `glTexSubImage2D`.
+    pub rects_uploaded: usize,
+    /// Total bytes of glyph coverage data uploaded.
+    pub bytes_uploaded: usize,
+}
+
+/// One dirty atlas rect, staged for upload by [`flush_pending_uploads`].
+/// Rects are uploaded individually (no union/row-packing), just batched
+/// under a single texture bind and spread across calls if the upload
+/// budget is tight.
+struct PendingUpload {
+    offset: [u16; 2],
+    size: [u16; 2],
+    data: Vec<u8>,
+}
+
+/// Uploads as many of `pending`'s staged rects as fit within `budget` bytes
+/// (all of them if `budget` is `None`) via a single texture bind followed by
+/// one `glTexSubImage2D` per uploaded rect, so one `process_queued` pass
+/// costs at most one state change plus a bounded number of sub-image calls
+/// instead of one bind-and-upload per dirty rect. The first rect is always
+/// uploaded, even if it alone exceeds `budget`. Rects that don't fit are
+/// left in `pending` for the next call.
+fn flush_pending_uploads(
+    gl: &glow::Context,
+    texture: glow::Texture,
+    format: u32,
+    pending: &mut VecDeque<PendingUpload>,
+    budget: Option<usize>,
+) -> UploadStats {
+    use glow::HasContext;
+
+    let mut stats = UploadStats::default();
+
+    if pending.is_empty() {
+        return stats;
+    }
+
+    unsafe {
+        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+    }
+
+    while let Some(update) = pending.front() {
+        let bytes = update.data.len();
+
+        // Always upload at least one rect per call, even over budget, so a
+        // single glyph larger than the budget doesn't stall forever.
+        if let Some(budget) = budget {
+            if stats.bytes_uploaded + bytes > budget && stats.rects_uploaded > 0 {
+                break;
+            }
+        }
+
+        let update = pending.pop_front().expect("front already checked");
+
+        unsafe {
+            gl.tex_sub_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                update.offset[0] as i32,
+                update.offset[1] as i32,
+                update.size[0] as i32,
+                update.size[1] as i32,
+                format,
+                glow::UNSIGNED_BYTE,
+                glow::PixelUnpackData::Slice(&update.data),
+            );
+        }
+
+        stats.rects_uploaded += 1;
+        stats.bytes_uploaded += bytes;
+    }
+
+    stats
+}
+
+/// Implemented by any GPU backend capable of drawing a buffer of vertices
+/// of type `V` produced from queued glyphs.
+///
+/// [`core::Pipeline`] and [`compatibility::Pipeline`] are the built-in
+/// implementations backing the default, `Extra`-based `GlyphBrush`; a
+/// custom vertex type `V` (see [`GlyphBrushBuilder::to_vertex`]) needs its
+/// own implementation supplied alongside a matching shader.
+///
+/// [`GlyphBrushBuilder::to_vertex`]: ../struct.GlyphBrushBuilder.html#method.to_vertex
+pub trait GlyphPipeline<V> {
+    /// Stages a sub-region of decoded glyph coverage data for upload to the
+    /// GPU texture cache. Staged updates aren't actually uploaded until the
+    /// next [`flush_cache_updates`](#tymethod.flush_cache_updates) call,
+    /// which batches every rect staged since the last call under a single
+    /// texture bind (each still gets its own `glTexSubImage2D`; rects
+    /// aren't merged into a combined region).
+    fn stage_cache_update(
+        &mut self,
+        offset: [u16; 2],
+        size: [u16; 2],
+        data: &[u8],
+    );
+
+    /// Uploads as many staged cache updates as fit within this pipeline's
+    /// upload budget (see
+    /// [`GlyphBrushBuilder::max_texture_upload_bytes_per_frame`]),
+    /// returning stats on what was actually uploaded. Any updates that don't
+    /// fit stay staged for a future call.
+    ///
+    /// [`GlyphBrushBuilder::max_texture_upload_bytes_per_frame`]: ../struct.GlyphBrushBuilder.html#method.max_texture_upload_bytes_per_frame
+    fn flush_cache_updates(&mut self, gl: &glow::Context) -> UploadStats;
+
+    /// Whether staged cache updates are still waiting for a future
+    /// [`flush_cache_updates`](#tymethod.flush_cache_updates) call because
+    /// the upload budget was exceeded. Callers should skip uploading new
+    /// vertices while this is `true`, so nothing draws against a
+    /// half-updated atlas.
+    fn has_pending_uploads(&self) -> bool;
+
+    /// Stats from the last [`flush_cache_updates`](#tymethod.flush_cache_updates)
+    /// call, for profiling. Defaults to all zeroes for pipelines that don't
+    /// track upload cost.
+    fn upload_stats(&self) -> UploadStats {
+        UploadStats::default()
+    }
+
+    /// The depth comparison function this pipeline was built with, see
+    /// [`GlyphBrushBuilder::depth_test`]. Used as the default `depth_func`
+    /// for [`GlyphBrush::draw_queued_with_transform_and_depth`]. Defaults to
+    /// `None` (letting the caller's own default apply) for pipelines that
+    /// don't track a depth-test setting.
+    ///
+    /// [`GlyphBrushBuilder::depth_test`]: ../struct.GlyphBrushBuilder.html#method.depth_test
+    /// [`GlyphBrush::draw_queued_with_transform_and_depth`]: ../struct.GlyphBrush.html#method.draw_queued_with_transform_and_depth
+    fn depth_test(&self) -> Option<u32> {
+        None
+    }
+
+    /// Re-allocates the GPU texture cache to a new size, discarding its
+    /// previous contents.
+    fn increase_cache_size(&mut self, gl: &glow::Context, width: u32, height: u32);
+
+    /// Replaces the vertices drawn by the next [`draw`](#tymethod.draw) call.
+    fn upload(&mut self, gl: &glow::Context, vertices: &[V]);
+
+    /// Draws the vertices uploaded by the last [`upload`](#tymethod.upload)
+    /// call, optionally scissored to `region`.
+    fn draw(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+    );
+
+    /// The largest texture dimension supported by this pipeline's context.
+    fn get_max_texture_size(&self) -> u32;
+
+    /// Like [`draw`](#tymethod.draw), but with `GL_DEPTH_TEST` enabled using
+    /// `depth_func` (e.g. `glow::LESS`), so glyphs are depth-tested against a
+    /// 3D scene using each vertex's `z`. The default implementation ignores
+    /// `depth_func` and just forwards to `draw`, for pipelines that don't
+    /// support depth testing.
+    fn draw_with_depth(
+        &mut self,
+        gl: &glow::Context,
+        transform: [f32; 16],
+        region: Option<Region>,
+        depth_func: u32,
+    ) {
+        let _ = depth_func;
+        self.draw(gl, transform, region);
+    }
+}
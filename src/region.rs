@@ -0,0 +1,12 @@
+/// A rectangular region of the target surface, used to scissor text drawing
+/// so that it only affects pixels inside the region.
+///
+/// Coordinates are in physical pixels and are passed unchanged to
+/// `glScissor`, so the origin is at the bottom left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}